@@ -1,11 +1,14 @@
+mod parser;
+
 use std::collections::VecDeque;
-use std::fs::File;
-use std::io::{Seek, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::sync::{Arc, Mutex};
 
 use chrono::Timelike;
 use crossterm::{cursor, terminal};
-use crossterm::event::{self, Event, KeyCode, KeyEvent};
-use crossterm::execute;
+use crossterm::event::{self, Event as CrosstermEvent, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::{execute, queue};
 use crossterm::style::{style, Color, Print, SetForegroundColor, StyledContent, Stylize};
 use tokio::sync::mpsc;
 use tokio::time;
@@ -14,33 +17,73 @@ const TIME_X: u16 = 14;
 const REFRESH_MS: u64 = 128;
 const SAVE_INTERVAL_S: u64 = 20;
 
+/// Everything the main loop can react to, decoupling render cadence from
+/// input: a dedicated task forwards key presses and resizes, a separate
+/// `tokio::time::interval` task emits `Tick` at `REFRESH_MS`, and the
+/// signal task emits `Quit` on SIGINT/SIGTERM. The main loop just `recv`s
+/// from one channel and dispatches on this enum.
+enum Event {
+    Key(KeyCode),
+    /// (width, height); only height is used today, but the full size is
+    /// kept here to match the terminal's own resize event shape.
+    #[allow(dead_code)]
+    Resize(u16, u16),
+    Tick,
+    /// A SIGINT/SIGTERM was received, or Ctrl-C was pressed (delivered as a
+    /// key event in raw mode); quit as if the user pressed 'q'.
+    Quit,
+}
+
+/// The cumulative running time: `running_total` plus whatever has elapsed
+/// in the current unpaused segment, or just `running_total` while paused.
+fn total_elapsed(paused: bool, start: time::Instant, running_total: time::Duration) -> time::Duration {
+    if paused {
+        running_total
+    } else {
+        running_total + start.elapsed()
+    }
+}
+
+/// Disable raw mode, show the cursor and leave the alternate screen, so the
+/// user's original shell contents reappear untouched. Best-effort: called
+/// both on normal exit and from the panic hook, where further I/O errors
+/// can't be usefully reported.
+fn restore_terminal() -> std::io::Result<()> {
+    let mut stdout = std::io::stdout();
+    execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()
+}
+
 struct Line {
-    message: StyledContent<&'static str>,
+    message: StyledContent<String>,
     timestamp: StyledContent<String>,
     color: Color,
 }
 
 impl Line {
-    fn new(message: &'static str, hms: (u8, u8, u8)) -> Line {
+    fn new(message: impl Into<String>, hms: (u64, u8, u8)) -> Line {
+        // Hours are printed at their natural width (not truncated to two
+        // digits) so cumulative totals past 99 hours still round-trip
+        // through `parser::parse_line`; only minutes/seconds are fixed-width.
         let timestamp = format!("{:02}:{:02}:{:02}", hms.0, hms.1, hms.2);
         Self {
-            message: style(message),
+            message: style(message.into()),
             timestamp: style(timestamp),
             color: Color::Reset,
         }
     }
-    fn from_duration(message: &'static str, d: time::Duration) -> Line {
+    fn from_duration(message: impl Into<String>, d: time::Duration) -> Line {
         let secs = d.as_secs();
         let hms = (
-            (secs / 3600) as u8,
+            secs / 3600,
             ((secs % 3600) / 60) as u8,
             (secs % 60) as u8,
         );
         Self::new(message, hms)
     }
-    fn from_datetime(message: &'static str, d: chrono::DateTime<chrono::Local>) -> Line {
+    fn from_datetime(message: impl Into<String>, d: chrono::DateTime<chrono::Local>) -> Line {
         let hms = (
-            d.hour() as u8,
+            d.hour() as u64,
             d.minute() as u8,
             d.second() as u8,
         );
@@ -61,8 +104,10 @@ impl Line {
         self
     }
 
-    fn print(&self, stdout: &mut std::io::Stdout, line: u16) {
-        execute!(
+    /// Enqueue this line's cursor moves and text without flushing; the
+    /// caller is responsible for flushing `stdout` once it's done drawing.
+    fn print(&self, stdout: &mut std::io::Stdout, line: u16) -> std::io::Result<()> {
+        queue!(
             stdout,
             SetForegroundColor(self.color),
             cursor::MoveTo(0, line),
@@ -70,7 +115,6 @@ impl Line {
             cursor::MoveTo(TIME_X, line),
             Print(&self.timestamp)
         )
-        .unwrap();
     }
 }
 
@@ -80,17 +124,41 @@ struct History {
     max_rows: u16,
     history_file: File,
     last_save: time::Instant,
+    /// A live mirror of the serialized history, kept in step with `lines`
+    /// on every write so the panic hook can flush it even when a panic
+    /// lands between periodic disk saves.
+    pending: Arc<Mutex<String>>,
 }
 
 impl History {
-    fn new(history_file: File) -> History {
-        Self {
-            lines: VecDeque::new(),
+    /// Build a `History` around an already-open history file, seeding the
+    /// viewport with `resumed` lines read back from a previous session (if
+    /// any), trimmed to however many rows the current terminal can show.
+    fn new(history_file: File, mut resumed: VecDeque<Line>, pending: Arc<Mutex<String>>) -> History {
+        let max_rows = terminal::size().unwrap().1.saturating_sub(6);
+        while resumed.len() as u16 > max_rows {
+            resumed.pop_front();
+        }
+        let history = Self {
+            lines: resumed,
             start_row: 4,
-            max_rows: terminal::size().unwrap().1 - 6,
+            max_rows,
             history_file,
             last_save: time::Instant::now(),
+            pending,
+        };
+        history.sync_pending();
+        history
+    }
+
+    /// Re-serialize `lines` into `pending`, so a panic hook reading it
+    /// always sees the latest in-memory state, not just the last flush.
+    fn sync_pending(&self) {
+        let mut contents = String::new();
+        for line in &self.lines {
+            contents.push_str(&format!("{} {}\n", line.message.content(), line.timestamp.content()));
         }
+        *self.pending.lock().unwrap_or_else(|e| e.into_inner()) = contents;
     }
 
     fn len(&self) -> u16 {
@@ -100,14 +168,39 @@ impl History {
         self.len() + self.start_row
     }
 
+    /// Recompute the viewport height after a terminal resize, dropping the
+    /// oldest retained lines if the new height can no longer hold them all,
+    /// and redraw everything at its new row.
+    fn resize(&mut self, new_height: u16, stdout: &mut std::io::Stdout) -> std::io::Result<()> {
+        self.max_rows = new_height.saturating_sub(6);
+        while self.len() > self.max_rows {
+            self.lines.pop_front();
+        }
+        self.redraw(stdout)
+    }
+
+    /// Re-print all retained lines at their current rows without touching
+    /// anything above `start_row` (the header and start-time line). Only
+    /// enqueues commands; the caller flushes `stdout` once it's done.
+    fn redraw(&self, stdout: &mut std::io::Stdout) -> std::io::Result<()> {
+        queue!(
+            stdout,
+            cursor::MoveToRow(self.start_row),
+            terminal::Clear(terminal::ClearType::FromCursorDown),
+        )?;
+        for (i, line) in self.lines.iter().enumerate() {
+            line.print(stdout, self.start_row + i as u16)?;
+        }
+        Ok(())
+    }
+
     fn update_history(&mut self) -> std::io::Result<()> {
+        self.sync_pending();
         // Clear the history file
         self.history_file.seek(std::io::SeekFrom::Start(0))?;
         self.history_file.set_len(0)?;
         // Write the current history
-        for line in &self.lines {
-            writeln!(self.history_file, "{} {}", line.message.content(), line.timestamp.content())?;
-        }
+        self.history_file.write_all(self.pending.lock().unwrap_or_else(|e| e.into_inner()).as_bytes())?;
         Ok(())
     }
 
@@ -121,46 +214,91 @@ impl History {
 
         if !advance {
             // Replace the current line
-            line.print(stdout, u16::max(self.start_row, self.active_line() - 1));
+            line.print(stdout, u16::max(self.start_row, self.active_line() - 1))?;
             self.lines.pop_back();
             self.lines.push_back(line);
+            self.sync_pending();
             return Ok(());
         }
 
         // Add a new line
         if self.len() < self.max_rows {
-            line.print(stdout, self.active_line());
+            line.print(stdout, self.active_line())?;
             self.lines.push_back(line);
+            self.sync_pending();
             return Ok(());
         }
-        
+
         self.lines.pop_front();
         self.lines.push_back(line);
-        
+
         // move all lines up
-        execute!(
+        queue!(
             stdout,
             cursor::MoveToRow(self.start_row),
             terminal::Clear(terminal::ClearType::FromCursorDown),
         )?;
         for (i, line) in self.lines.iter().enumerate() {
-            line.print(stdout, self.start_row + i as u16);
+            line.print(stdout, self.start_row + i as u16)?;
         }
 
+        self.sync_pending();
         Ok(())
     }
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let args = std::env::args();
-    let save_file = match args.skip(1).next() {
-        Some(filename) => File::create(filename),
-        None => File::create(".datimer.history"),
-    }?;
+    let mut args = std::env::args();
+    let filename = args.nth(1).unwrap_or_else(|| ".datimer.history".to_string());
+
+    // If a history file from a previous run already exists, parse it back
+    // into the viewport and recover the accumulated running total instead
+    // of truncating it, so the timer is cumulative across runs.
+    let mut save_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&filename)?;
+    let mut saved_contents = String::new();
+    save_file.read_to_string(&mut saved_contents)?;
+
+    let mut resumed_lines = VecDeque::new();
+    let mut running_total = time::Duration::from_secs(0);
+    for line in saved_contents.lines() {
+        if let Some(parsed) = parser::parse_line(line) {
+            if parsed.message == "Elapsed:" {
+                running_total = parsed.duration();
+            }
+            resumed_lines.push_back(Line::new(parsed.message, parsed.hms));
+        }
+    }
+    let resumed = !resumed_lines.is_empty();
+
+    // A live mirror of the serialized history (see `History::sync_pending`),
+    // shared with the panic hook so it can flush lines written since the
+    // last periodic save, not just whatever already made it to disk.
+    let pending_history = Arc::new(Mutex::new(String::new()));
+
+    // Leave the terminal exactly as we found it even if we panic, and save
+    // whatever history is pending so a panic loses no more than the redraw
+    // that was in flight.
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook({
+        let pending_history = Arc::clone(&pending_history);
+        let filename = filename.clone();
+        Box::new(move |info| {
+            let _ = restore_terminal();
+            let contents = pending_history.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = std::fs::write(&filename, &*contents);
+            default_panic_hook(info);
+        })
+    });
 
-    terminal::enable_raw_mode()?;
     let mut stdout = std::io::stdout();
+    execute!(stdout, terminal::EnterAlternateScreen)?;
+    terminal::enable_raw_mode()?;
     execute!(
         stdout,
         cursor::Hide,
@@ -172,78 +310,155 @@ async fn main() -> std::io::Result<()> {
         cursor::MoveTo(0, 0),
         Print("DATIMER".bold()),
         cursor::MoveTo(0, 1),
-        Print("Press 'p' to pause, 'q' to quit")
+        Print("Press 'p' to pause, 'l' to lap, 'q' to quit")
     )?;
 
     // Create a channel for communication between tasks
-    let (tx, mut rx) = mpsc::channel(1);
+    let (tx, mut rx) = mpsc::channel(16);
+
+    // Spawn a task that turns an external SIGTERM (or a SIGINT raised from
+    // outside this process, e.g. `kill -INT`) into a graceful Quit event.
+    // Raw mode clears ISIG, so a Ctrl-C typed at this terminal never reaches
+    // us as a signal; that case is instead caught as a keypress below.
+    tokio::spawn({
+        let tx = tx.clone();
+        async move {
+            #[cfg(unix)]
+            {
+                let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                    .expect("failed to install SIGTERM handler");
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+            let _ = tx.send(Event::Quit).await;
+        }
+    });
 
-    // Spawn a task to listen for key presses
+    // Spawn a task emitting a steady Tick, decoupled from input polling.
+    tokio::spawn({
+        let tx = tx.clone();
+        async move {
+            let mut interval = time::interval(time::Duration::from_millis(REFRESH_MS));
+            loop {
+                interval.tick().await;
+                if tx.send(Event::Tick).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    // Spawn a task to listen for key presses and terminal resizes. Ctrl-C is
+    // caught here rather than as SIGINT: raw mode clears ISIG, so crossterm
+    // delivers it as an ordinary key event with the CONTROL modifier set.
     tokio::spawn(async move {
         loop {
-            if let Ok(Event::Key(KeyEvent { code, .. })) = event::read() {
-                if let KeyCode::Char(c) = code {
-                    if tx.send(c).await.is_err() {
-                        return;
-                    }
-                    if c == 'q' {
-                        return;
-                    }
+            let input = match event::read() {
+                Ok(CrosstermEvent::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                })) => Some(Event::Quit),
+                Ok(CrosstermEvent::Key(KeyEvent { code, .. })) => Some(Event::Key(code)),
+                Ok(CrosstermEvent::Resize(width, height)) => Some(Event::Resize(width, height)),
+                _ => None,
+            };
+            if let Some(input) = input {
+                let quit = matches!(input, Event::Key(KeyCode::Char('q')) | Event::Quit);
+                if tx.send(input).await.is_err() || quit {
+                    return;
                 }
             }
         }
     });
 
-    let mut running_total = time::Duration::from_secs(0);
     let mut start = time::Instant::now();
-    let mut paused = false;
-    Line::from_datetime("Start time:", chrono::Local::now()).color(Color::Cyan).print(&mut stdout, 3);
-
-    let mut history = History::new(save_file);
-
-    // Every REFRESH_MS ms, print the elapsed time
-    // If there is a key press, exit the loop
-    loop {
-        if !paused {
-            let elapsed = start.elapsed();
-            let line = Line::from_duration("Elapsed:", running_total + elapsed).color(Color::Reset).bold();
-            history.write_line(line, &mut stdout, false)?;
-        }
-        if let Ok(c) = rx.try_recv() {
-            match c {
-                'q' => break,
-                'p' | ' ' => {
-                    if paused {
-                        // resume
-                        start = time::Instant::now();
-                        let line = Line::from_datetime("Resumed at:", chrono::Local::now()).color(Color::Green);
-                        history.write_line(line, &mut stdout, false)?;
-                        let line = Line::from_duration("Elapsed:", running_total).color(Color::Reset).bold();
-                        history.write_line(line, &mut stdout, true)?;
-                    } else {
-                        // pause
-                        running_total += start.elapsed();
-                        let line = Line::from_datetime("Paused at:", chrono::Local::now()).color(Color::Red);
-                        history.write_line(line, &mut stdout, false)?;
-                        let line = Line::from_duration("Elapsed:", running_total).color(Color::Reset).italic();
-                        history.write_line(line, &mut stdout, true)?;
-                    }
-                    paused = !paused;
+    // A resumed session starts paused, so the user explicitly resumes it.
+    let mut paused = resumed;
+    // The cumulative elapsed time as of the last lap marker, and how many
+    // laps have been recorded this session.
+    let mut last_split = running_total;
+    let mut lap_count: u32 = 0;
+    let start_line = Line::from_datetime("Start time:", chrono::Local::now()).color(Color::Cyan);
+    start_line.print(&mut stdout, 3)?;
+
+    let mut history = History::new(save_file, resumed_lines, pending_history);
+    history.redraw(&mut stdout)?;
+    stdout.flush()?;
+
+    // Dispatch on whatever event comes in next; rendering cadence (Tick),
+    // key presses, resizes and quit signals all flow through one channel.
+    while let Some(event) = rx.recv().await {
+        match event {
+            Event::Tick => {
+                if !paused {
+                    let elapsed = start.elapsed();
+                    let line = Line::from_duration("Elapsed:", running_total + elapsed).color(Color::Reset).bold();
+                    history.write_line(line, &mut stdout, false)?;
                 }
-                _ => (),
             }
+            Event::Key(KeyCode::Char('q')) | Event::Quit => break,
+            Event::Key(KeyCode::Char('p')) | Event::Key(KeyCode::Char(' ')) => {
+                if paused {
+                    // resume
+                    start = time::Instant::now();
+                    let line = Line::from_datetime("Resumed at:", chrono::Local::now()).color(Color::Green);
+                    history.write_line(line, &mut stdout, false)?;
+                    let line = Line::from_duration("Elapsed:", running_total).color(Color::Reset).bold();
+                    history.write_line(line, &mut stdout, true)?;
+                } else {
+                    // pause
+                    running_total += start.elapsed();
+                    let line = Line::from_datetime("Paused at:", chrono::Local::now()).color(Color::Red);
+                    history.write_line(line, &mut stdout, false)?;
+                    let line = Line::from_duration("Elapsed:", running_total).color(Color::Reset).italic();
+                    history.write_line(line, &mut stdout, true)?;
+                }
+                paused = !paused;
+            }
+            Event::Key(KeyCode::Char('l')) => {
+                // Drop a labeled split: the duration since the previous
+                // split, with paused stretches excluded on both sides. The
+                // frozen Elapsed row stays put; push the lap below it, then
+                // a fresh Elapsed placeholder to keep ticking from there.
+                let elapsed = total_elapsed(paused, start, running_total);
+                let segment = elapsed.saturating_sub(last_split);
+                last_split = elapsed;
+                lap_count += 1;
+                let lap_line = Line::from_duration(format!("Lap {lap_count}:"), segment).color(Color::Magenta);
+                history.write_line(lap_line, &mut stdout, true)?;
+                let elapsed_line = Line::from_duration("Elapsed:", elapsed).color(Color::Reset).bold();
+                history.write_line(elapsed_line, &mut stdout, true)?;
+            }
+            Event::Resize(_, height) => {
+                history.resize(height, &mut stdout)?;
+                queue!(
+                    stdout,
+                    cursor::MoveTo(0, 0),
+                    Print("DATIMER".bold()),
+                    cursor::MoveTo(0, 1),
+                    Print("Press 'p' to pause, 'l' to lap, 'q' to quit"),
+                )?;
+                start_line.print(&mut stdout, 3)?;
+            }
+            Event::Key(_) => (),
         }
-        time::sleep(time::Duration::from_millis(REFRESH_MS)).await;
+        // Flush everything queued up while handling this event in one go,
+        // rather than on every individual cursor move and print.
+        stdout.flush()?;
     }
 
-    // Restore the terminal to its original state
-    execute!(
-        stdout,
-        SetForegroundColor(Color::Reset),
-        cursor::MoveTo(0, history.active_line() + 2),
-        cursor::Show
-    )?;
-    terminal::disable_raw_mode()?;
+    // Flush the final history to disk, then restore the terminal to its
+    // original state, whether we got here via 'q' or a SIGINT/SIGTERM quit.
+    history.update_history()?;
+    execute!(stdout, SetForegroundColor(Color::Reset))?;
+    restore_terminal()?;
 
     Ok(())
 }
@@ -0,0 +1,109 @@
+//! Parses history lines previously written by `History::update_history`, so a
+//! prior run's `<message> HH:MM:SS` lines can be replayed back into a
+//! resumed session. Malformed lines (from manual edits, truncation, etc.)
+//! are skipped rather than treated as a fatal error.
+
+use tokio::time::Duration;
+
+/// One successfully parsed history line.
+pub struct ParsedLine {
+    pub message: String,
+    /// Hours, minutes, seconds. Hours is `u64` rather than `u8`: cumulative
+    /// elapsed time is unbounded and `Line::from_duration` prints it at its
+    /// natural width past two digits, so the parser must accept that too.
+    pub hms: (u64, u8, u8),
+}
+
+impl ParsedLine {
+    /// The `HH:MM:SS` timestamp converted back into a `Duration`.
+    pub fn duration(&self) -> Duration {
+        let (h, m, s) = self.hms;
+        Duration::from_secs(h * 3600 + m as u64 * 60 + s as u64)
+    }
+}
+
+/// Split a saved history line into its leading message and trailing
+/// `HH:MM:SS` timestamp. Returns `None` if the line doesn't end in a
+/// timestamp with two-digit minutes/seconds (hours may be wider).
+pub fn parse_line(line: &str) -> Option<ParsedLine> {
+    let (message, timestamp) = line.trim_end().rsplit_once(' ')?;
+    let hms = parse_hms(timestamp)?;
+    Some(ParsedLine {
+        message: message.to_string(),
+        hms,
+    })
+}
+
+/// Parse a `HH:MM:SS` token. Minutes and seconds must be exactly two digits;
+/// hours may be two digits or wider, since `Line::from_duration` prints
+/// cumulative totals past 99 hours at their natural width rather than
+/// truncating them.
+fn parse_hms(s: &str) -> Option<(u64, u8, u8)> {
+    let mut fields = s.split(':');
+    let hours = fields.next()?;
+    let minutes = fields.next()?;
+    let seconds = fields.next()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    if hours.len() < 2 || minutes.len() != 2 || seconds.len() != 2 {
+        return None;
+    }
+    Some((hours.parse().ok()?, minutes.parse().ok()?, seconds.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_simple_line() {
+        let parsed = parse_line("Elapsed: 01:02:03").unwrap();
+        assert_eq!(parsed.message, "Elapsed:");
+        assert_eq!(parsed.hms, (1, 2, 3));
+    }
+
+    #[test]
+    fn keeps_spaces_in_the_message() {
+        let parsed = parse_line("Lap 3 (split):  00:05:30").unwrap();
+        assert_eq!(parsed.message, "Lap 3 (split): ");
+        assert_eq!(parsed.hms, (0, 5, 30));
+    }
+
+    #[test]
+    fn duration_converts_hms_to_seconds() {
+        let parsed = parse_line("Elapsed: 01:02:03").unwrap();
+        assert_eq!(parsed.duration(), Duration::from_secs(3723));
+    }
+
+    #[test]
+    fn rejects_single_digit_fields() {
+        assert!(parse_line("Elapsed: 1:02:03").is_none());
+        assert!(parse_line("Elapsed: 01:2:03").is_none());
+        assert!(parse_line("Elapsed: 01:02:3").is_none());
+    }
+
+    #[test]
+    fn rejects_a_trailing_extra_field() {
+        assert!(parse_line("Elapsed: 01:02:03:").is_none());
+        assert!(parse_line("Elapsed: 01:02:03:04").is_none());
+    }
+
+    #[test]
+    fn rejects_lines_with_no_timestamp() {
+        assert!(parse_line("Elapsed:").is_none());
+        assert!(parse_line("").is_none());
+    }
+
+    #[test]
+    fn rejects_non_numeric_fields() {
+        assert!(parse_line("Elapsed: aa:02:03").is_none());
+    }
+
+    #[test]
+    fn round_trips_hours_past_two_digits() {
+        let parsed = parse_line("Elapsed: 123:02:03").unwrap();
+        assert_eq!(parsed.hms, (123, 2, 3));
+        assert_eq!(parsed.duration(), Duration::from_secs(123 * 3600 + 123));
+    }
+}